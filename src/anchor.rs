@@ -0,0 +1,159 @@
+use crate::text::{Bias, TextEdit, TextPosition};
+use ropey::Rope;
+use std::convert::TryFrom;
+
+/// A position in a document that survives edits: a byte offset paired with a [`Bias`] that says
+/// which side of an edit the anchor should stick to when the edit lands exactly on it. Useful for
+/// diagnostics, decorations, or bracket-match markers that must keep tracking the same logical
+/// place in the document as it's mutated, instead of going stale the moment an edit is applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Anchor {
+    pub byte: usize,
+    pub bias: Bias,
+}
+
+impl Anchor {
+    /// An anchor that stays put when an edit starts exactly at `byte`.
+    pub fn anchor_before(byte: usize) -> Self {
+        Anchor { byte, bias: Bias::Before }
+    }
+
+    /// An anchor that moves past an edit that starts exactly at `byte`.
+    pub fn anchor_after(byte: usize) -> Self {
+        Anchor { byte, bias: Bias::After }
+    }
+
+    /// Updates this anchor in place for a single edit: an anchor before the edit is unchanged, an
+    /// anchor after it shifts by the edit's length delta, and an anchor inside the replaced range
+    /// collapses to the edit's start (`Bias::Before`) or new end (`Bias::After`).
+    pub fn apply_edit(&mut self, edit: &TextEdit) {
+        let start = edit.input_edit.start_byte() as usize;
+        let old_end = edit.input_edit.old_end_byte() as usize;
+        let new_end = edit.input_edit.new_end_byte() as usize;
+
+        self.byte = if self.byte == start && start == old_end {
+            match self.bias {
+                Bias::Before => start,
+                Bias::After => new_end,
+            }
+        } else if self.byte < start {
+            self.byte
+        } else if self.byte >= old_end {
+            let delta = new_end as isize - old_end as isize;
+            (self.byte as isize + delta) as usize
+        } else {
+            match self.bias {
+                Bias::Before => start,
+                Bias::After => new_end,
+            }
+        };
+    }
+
+    /// Resolves this anchor to a concrete [`TextPosition`] against the current state of `rope`.
+    pub fn resolve(&self, rope: &Rope) -> TextPosition {
+        let char_idx = rope.byte_to_char(self.byte);
+        let line_idx = rope.byte_to_line(self.byte);
+        let line_byte_idx = rope.line_to_byte(line_idx);
+
+        let point = tree_sitter::Point::new(
+            u32::try_from(line_idx).unwrap(),
+            u32::try_from(self.byte - line_byte_idx).unwrap(),
+        );
+
+        TextPosition {
+            char: u32::try_from(char_idx).unwrap(),
+            byte: u32::try_from(self.byte).unwrap(),
+            code: u32::try_from(rope.char_to_utf16_cu(char_idx)).unwrap(),
+            point,
+        }
+    }
+}
+
+/// A registered collection of [`Anchor`]s that are kept up to date together as edits are applied,
+/// so a caller can hold a `DiagnosticSet`-style collection of ranges without recomputing them from
+/// scratch on every document mutation.
+#[derive(Clone, Debug, Default)]
+pub struct AnchorSet {
+    anchors: Vec<Anchor>,
+}
+
+/// A stable handle to an anchor registered in an [`AnchorSet`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AnchorId(usize);
+
+impl AnchorSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, anchor: Anchor) -> AnchorId {
+        self.anchors.push(anchor);
+        AnchorId(self.anchors.len() - 1)
+    }
+
+    pub fn get(&self, id: AnchorId) -> Anchor {
+        self.anchors[id.0]
+    }
+
+    /// Updates every registered anchor in place for a single edit.
+    pub fn apply_edit(&mut self, edit: &TextEdit) {
+        for anchor in &mut self.anchors {
+            anchor.apply_edit(edit);
+        }
+    }
+
+    pub fn resolve_all<'rope>(&'rope self, rope: &'rope Rope) -> impl Iterator<Item = TextPosition> + 'rope {
+        self.anchors.iter().map(move |anchor| anchor.resolve(rope))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert_edit(at: usize, len: usize) -> TextEdit<'static> {
+        let point = tree_sitter::Point::new(0, 0);
+        let input_edit = tree_sitter::InputEdit::new(
+            at as u32,
+            at as u32,
+            (at + len) as u32,
+            &point,
+            &point,
+            &point,
+        );
+        TextEdit {
+            input_edit,
+            start_char_idx: at,
+            end_char_idx: at,
+            text: "".into(),
+        }
+    }
+
+    #[test]
+    fn anchor_before_stays_put_on_an_insert_at_its_position() {
+        let mut anchor = Anchor::anchor_before(5);
+        anchor.apply_edit(&insert_edit(5, 3));
+        assert_eq!(anchor.byte, 5);
+    }
+
+    #[test]
+    fn anchor_after_shifts_past_an_insert_at_its_position() {
+        let mut anchor = Anchor::anchor_after(5);
+        anchor.apply_edit(&insert_edit(5, 3));
+        assert_eq!(anchor.byte, 8);
+    }
+
+    #[test]
+    fn anchor_strictly_before_the_edit_is_unaffected() {
+        let mut anchor = Anchor::anchor_after(5);
+        anchor.apply_edit(&insert_edit(10, 3));
+        assert_eq!(anchor.byte, 5);
+    }
+
+    #[test]
+    fn anchor_strictly_after_the_edit_shifts_by_the_delta() {
+        let mut anchor = Anchor::anchor_before(10);
+        anchor.apply_edit(&insert_edit(5, 3));
+        assert_eq!(anchor.byte, 13);
+    }
+}
@@ -0,0 +1,465 @@
+use crate::{
+    rope::RopeExt,
+    text::{Bias, PositionEncoding, TextEdit},
+};
+use ropey::Rope;
+use std::convert::TryFrom;
+
+/// One run of a [`ChangeSet`], expressed against the document as it stood *before* the
+/// changeset. Each variant carries its own text so spans can be split, merged, and inverted
+/// without needing to go back to a source rope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum ChangeOp {
+    Retain(String),
+    Delete(String),
+    Insert(String),
+}
+
+fn op_len_chars(op: &ChangeOp) -> usize {
+    match op {
+        ChangeOp::Retain(s) | ChangeOp::Delete(s) | ChangeOp::Insert(s) => s.chars().count(),
+    }
+}
+
+fn split_str_at_chars(s: &str, n: usize) -> (&str, &str) {
+    match s.char_indices().nth(n) {
+        Some((byte_idx, _)) => s.split_at(byte_idx),
+        None => (s, ""),
+    }
+}
+
+/// Splits `op` at `len` chars, returning the head (at most `len` chars long) and, if anything
+/// remains, the tail as a fresh op of the same variant.
+fn split_op(op: ChangeOp, len: usize) -> (ChangeOp, Option<ChangeOp>) {
+    if len >= op_len_chars(&op) {
+        return (op, None);
+    }
+    match op {
+        ChangeOp::Retain(s) => {
+            let (head, tail) = split_str_at_chars(&s, len);
+            (ChangeOp::Retain(head.to_string()), Some(ChangeOp::Retain(tail.to_string())))
+        },
+        ChangeOp::Delete(s) => {
+            let (head, tail) = split_str_at_chars(&s, len);
+            (ChangeOp::Delete(head.to_string()), Some(ChangeOp::Delete(tail.to_string())))
+        },
+        ChangeOp::Insert(s) => {
+            let (head, tail) = split_str_at_chars(&s, len);
+            (ChangeOp::Insert(head.to_string()), Some(ChangeOp::Insert(tail.to_string())))
+        },
+    }
+}
+
+/// Appends `op` to `ops`, merging it into a trailing op of the same variant when possible and
+/// dropping empty runs.
+fn push_coalesced(ops: &mut Vec<ChangeOp>, op: ChangeOp) {
+    if op_len_chars(&op) == 0 {
+        return;
+    }
+    match (ops.last_mut(), op) {
+        (Some(ChangeOp::Retain(prev)), ChangeOp::Retain(s)) => prev.push_str(&s),
+        (Some(ChangeOp::Delete(prev)), ChangeOp::Delete(s)) => prev.push_str(&s),
+        (Some(ChangeOp::Insert(prev)), ChangeOp::Insert(s)) => prev.push_str(&s),
+        (_, op) => ops.push(op),
+    }
+}
+
+#[derive(Clone, Default)]
+struct Cursor {
+    byte: usize,
+    row: u32,
+    column: u32,
+}
+
+impl Cursor {
+    fn advance(&mut self, text: &str) {
+        for b in text.bytes() {
+            self.byte += 1;
+            if b == b'\n' {
+                self.row += 1;
+                self.column = 0;
+            } else {
+                self.column += 1;
+            }
+        }
+    }
+
+    fn point(&self) -> tree_sitter::Point {
+        tree_sitter::Point::new(self.row, self.column)
+    }
+}
+
+/// A sequence of retain/delete/insert runs describing how to transform one document state into
+/// another. A `didChange` notification's ordered batch of content changes can be folded into a
+/// single `ChangeSet` via [`ChangeSet::compose_changes`], so the whole batch can be applied,
+/// mapped through, and inverted as one unit instead of one [`TextEdit`] at a time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChangeSet {
+    ops: Vec<ChangeOp>,
+}
+
+impl ChangeSet {
+    /// The no-op changeset over `base`: applying it leaves the rope unchanged.
+    pub fn identity(base: &Rope) -> Self {
+        let mut ops = Vec::new();
+        if base.len_chars() > 0 {
+            ops.push(ChangeOp::Retain(base.to_string()));
+        }
+        ChangeSet { ops }
+    }
+
+    /// Builds the changeset for a single [`TextEdit`] applied to `base`.
+    pub fn from_edit(base: &Rope, edit: &TextEdit) -> Self {
+        let mut ops = Vec::new();
+
+        if edit.start_char_idx > 0 {
+            ops.push(ChangeOp::Retain(base.slice(0 .. edit.start_char_idx).to_string()));
+        }
+        if edit.end_char_idx > edit.start_char_idx {
+            ops.push(ChangeOp::Delete(
+                base.slice(edit.start_char_idx .. edit.end_char_idx).to_string(),
+            ));
+        }
+        if !edit.text.is_empty() {
+            ops.push(ChangeOp::Insert(edit.text.to_string()));
+        }
+        if edit.end_char_idx < base.len_chars() {
+            ops.push(ChangeOp::Retain(base.slice(edit.end_char_idx ..).to_string()));
+        }
+
+        ChangeSet { ops }
+    }
+
+    /// Folds an ordered batch of `didChange` content changes into a single changeset. Each
+    /// change's range is resolved against the document as it stands after the preceding changes,
+    /// exactly as the LSP spec requires.
+    pub fn compose_changes(
+        base: &Rope,
+        changes: &[lsp::TextDocumentContentChangeEvent],
+        encoding: PositionEncoding,
+    ) -> anyhow::Result<Self> {
+        let mut rope = base.clone();
+        let mut result = ChangeSet::identity(base);
+
+        for change in changes {
+            let edit = rope.build_edit(change, encoding)?;
+            result = result.compose(&ChangeSet::from_edit(&rope, &edit));
+            rope.apply_edit(&edit);
+        }
+
+        Ok(result)
+    }
+
+    /// Composes `self` with `other`, producing the changeset equivalent to applying `self` and
+    /// then `other` in sequence. `other` must be expressed against the document that results from
+    /// applying `self` to its base.
+    pub fn compose(&self, other: &ChangeSet) -> ChangeSet {
+        let mut ops = Vec::new();
+
+        let mut a = self.ops.iter().cloned();
+        let mut b = other.ops.iter().cloned();
+        let mut a_cur = a.next();
+        let mut b_cur = b.next();
+
+        loop {
+            if a_cur.is_none() {
+                a_cur = a.next();
+            }
+            if b_cur.is_none() {
+                b_cur = b.next();
+            }
+
+            if matches!(a_cur, Some(ChangeOp::Delete(_))) {
+                push_coalesced(&mut ops, a_cur.take().unwrap());
+                continue;
+            }
+            if matches!(b_cur, Some(ChangeOp::Insert(_))) {
+                push_coalesced(&mut ops, b_cur.take().unwrap());
+                continue;
+            }
+
+            match (a_cur.take(), b_cur.take()) {
+                (None, None) => break,
+                (Some(av), None) => push_coalesced(&mut ops, av),
+                (None, Some(bv)) => push_coalesced(&mut ops, bv),
+                (Some(av), Some(bv)) => {
+                    let len = op_len_chars(&av).min(op_len_chars(&bv));
+                    let (a_head, a_rest) = split_op(av, len);
+                    let (b_head, b_rest) = split_op(bv, len);
+
+                    match (&a_head, &b_head) {
+                        (ChangeOp::Retain(_), ChangeOp::Retain(_)) => push_coalesced(&mut ops, a_head),
+                        (ChangeOp::Insert(_), ChangeOp::Retain(_)) => push_coalesced(&mut ops, a_head),
+                        (ChangeOp::Retain(_), ChangeOp::Delete(s)) => {
+                            push_coalesced(&mut ops, ChangeOp::Delete(s.clone()))
+                        },
+                        (ChangeOp::Insert(_), ChangeOp::Delete(_)) => {
+                            // an insert immediately deleted by the next change cancels out
+                        },
+                        _ => unreachable!("a `Delete`/`Insert` run never pairs with itself across changesets"),
+                    }
+
+                    a_cur = a_rest;
+                    b_cur = b_rest;
+                },
+            }
+        }
+
+        ChangeSet { ops }
+    }
+
+    /// Maps an old char offset forward through this changeset to the corresponding offset in the
+    /// resulting document. An offset that falls inside a deleted span collapses to the edit's
+    /// start; `bias` breaks the tie when the offset sits exactly at an inserted span, choosing
+    /// whether it stays before or moves after the insertion.
+    pub fn map_pos(&self, char_idx: usize, bias: Bias) -> usize {
+        let mut old_idx = 0;
+        let mut new_idx = 0;
+
+        for op in &self.ops {
+            match op {
+                ChangeOp::Retain(s) => {
+                    let len = s.chars().count();
+                    if char_idx < old_idx + len {
+                        return new_idx + (char_idx - old_idx);
+                    }
+                    old_idx += len;
+                    new_idx += len;
+                },
+                ChangeOp::Delete(s) => {
+                    let len = s.chars().count();
+                    if char_idx < old_idx + len {
+                        return new_idx;
+                    }
+                    old_idx += len;
+                },
+                ChangeOp::Insert(s) => {
+                    let len = s.chars().count();
+                    if old_idx == char_idx {
+                        return match bias {
+                            Bias::Before => new_idx,
+                            Bias::After => new_idx + len,
+                        };
+                    }
+                    new_idx += len;
+                },
+            }
+        }
+
+        new_idx + char_idx.saturating_sub(old_idx)
+    }
+
+    /// The changeset that undoes this one: retains stay put, deletes become inserts and vice
+    /// versa.
+    pub fn invert(&self) -> ChangeSet {
+        let ops = self
+            .ops
+            .iter()
+            .map(|op| match op {
+                ChangeOp::Retain(s) => ChangeOp::Retain(s.clone()),
+                ChangeOp::Delete(s) => ChangeOp::Insert(s.clone()),
+                ChangeOp::Insert(s) => ChangeOp::Delete(s.clone()),
+            })
+            .collect();
+        ChangeSet { ops }
+    }
+
+    /// Applies this changeset to `rope` in a single left-to-right pass, equivalent to applying
+    /// each constituent edit individually but without re-deriving offsets against the mutated
+    /// rope after every step.
+    pub fn apply(&self, rope: &mut Rope) {
+        let mut char_idx = 0;
+        for op in &self.ops {
+            match op {
+                ChangeOp::Retain(s) => char_idx += s.chars().count(),
+                ChangeOp::Delete(s) => {
+                    let len = s.chars().count();
+                    rope.remove(char_idx .. char_idx + len);
+                },
+                ChangeOp::Insert(s) => {
+                    rope.insert(char_idx, s);
+                    char_idx += s.chars().count();
+                },
+            }
+        }
+    }
+
+    /// Coalesces the changeset into the minimal ordered sequence of [`tree_sitter::InputEdit`]s a
+    /// downstream parser needs to re-sync, merging each adjacent delete/insert run into one edit.
+    pub fn input_edits(&self) -> Vec<tree_sitter::InputEdit> {
+        let mut edits = Vec::new();
+        let mut cursor = Cursor::default();
+        let mut ops = self.ops.iter().peekable();
+
+        while let Some(op) = ops.next() {
+            match op {
+                ChangeOp::Retain(s) => cursor.advance(s),
+                ChangeOp::Delete(_) | ChangeOp::Insert(_) => {
+                    let start_byte = cursor.byte;
+                    let start_point = cursor.point();
+
+                    let mut old_cursor = cursor.clone();
+                    let mut new_cursor = cursor.clone();
+
+                    let mut current = Some(op);
+                    loop {
+                        match current {
+                            Some(ChangeOp::Delete(s)) => old_cursor.advance(s),
+                            Some(ChangeOp::Insert(s)) => new_cursor.advance(s),
+                            _ => {},
+                        }
+                        current = match (current, ops.peek()) {
+                            (Some(ChangeOp::Delete(_)), Some(ChangeOp::Insert(_))) => ops.next(),
+                            (Some(ChangeOp::Insert(_)), Some(ChangeOp::Delete(_))) => ops.next(),
+                            _ => break,
+                        };
+                    }
+
+                    edits.push(tree_sitter::InputEdit::new(
+                        u32::try_from(start_byte).unwrap(),
+                        u32::try_from(old_cursor.byte).unwrap(),
+                        u32::try_from(new_cursor.byte).unwrap(),
+                        &start_point,
+                        &old_cursor.point(),
+                        &new_cursor.point(),
+                    ));
+
+                    cursor = new_cursor;
+                },
+            }
+        }
+
+        edits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(
+        start_line: u32,
+        start_char: u32,
+        end_line: u32,
+        end_char: u32,
+        text: &str,
+    ) -> lsp::TextDocumentContentChangeEvent {
+        lsp::TextDocumentContentChangeEvent {
+            range: Some(lsp::Range::new(
+                lsp::Position::new(start_line, start_char),
+                lsp::Position::new(end_line, end_char),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn apply_matches_sequential_edit_application() {
+        let base = Rope::from_str("abcdef");
+        let changes = vec![change(0, 0, 0, 2, "AB"), change(0, 4, 0, 6, "EF")];
+
+        let mut sequential = base.clone();
+        for c in &changes {
+            let edit = sequential.build_edit(c, PositionEncoding::Utf8).unwrap();
+            sequential.apply_edit(&edit);
+        }
+
+        let changeset = ChangeSet::compose_changes(&base, &changes, PositionEncoding::Utf8).unwrap();
+        let mut via_changeset = base.clone();
+        changeset.apply(&mut via_changeset);
+
+        assert_eq!(via_changeset.to_string(), "ABcdEF");
+        assert_eq!(via_changeset.to_string(), sequential.to_string());
+    }
+
+    #[test]
+    fn compose_is_associative() {
+        let base = Rope::from_str("abcdef");
+
+        let mut r0 = base.clone();
+        let edit_a = r0.build_edit(&change(0, 0, 0, 2, "AB"), PositionEncoding::Utf8).unwrap();
+        let a = ChangeSet::from_edit(&r0, &edit_a);
+        r0.apply_edit(&edit_a);
+
+        let mut r1 = r0.clone();
+        let edit_b = r1.build_edit(&change(0, 2, 0, 4, "CD"), PositionEncoding::Utf8).unwrap();
+        let b = ChangeSet::from_edit(&r1, &edit_b);
+        r1.apply_edit(&edit_b);
+
+        let mut r2 = r1.clone();
+        let edit_c = r2.build_edit(&change(0, 4, 0, 6, "EF"), PositionEncoding::Utf8).unwrap();
+        let c = ChangeSet::from_edit(&r2, &edit_c);
+
+        let left = a.compose(&b).compose(&c);
+        let right = a.compose(&b.compose(&c));
+
+        let mut via_left = base.clone();
+        left.apply(&mut via_left);
+        let mut via_right = base.clone();
+        right.apply(&mut via_right);
+
+        assert_eq!(via_left.to_string(), "ABCDEF");
+        assert_eq!(via_left.to_string(), via_right.to_string());
+    }
+
+    #[test]
+    fn compose_cancels_an_insert_immediately_deleted_by_the_next_change() {
+        let base = Rope::from_str("ac");
+
+        let mut scratch = base.clone();
+        let insert_edit = scratch.build_edit(&change(0, 1, 0, 1, "B"), PositionEncoding::Utf8).unwrap();
+        let insert_changeset = ChangeSet::from_edit(&scratch, &insert_edit);
+        scratch.apply_edit(&insert_edit);
+        assert_eq!(scratch.to_string(), "aBc");
+
+        let delete_edit = scratch.build_edit(&change(0, 1, 0, 2, ""), PositionEncoding::Utf8).unwrap();
+        let delete_changeset = ChangeSet::from_edit(&scratch, &delete_edit);
+
+        let composed = insert_changeset.compose(&delete_changeset);
+        let mut rope = base.clone();
+        composed.apply(&mut rope);
+
+        assert_eq!(rope.to_string(), "ac");
+    }
+
+    #[test]
+    fn invert_round_trips_back_to_the_original_document() {
+        let base = Rope::from_str("hello world");
+        let mut scratch = base.clone();
+        let edit = scratch.build_edit(&change(0, 6, 0, 11, "rust"), PositionEncoding::Utf8).unwrap();
+        let changeset = ChangeSet::from_edit(&scratch, &edit);
+
+        let mut rope = base.clone();
+        changeset.apply(&mut rope);
+        assert_eq!(rope.to_string(), "hello rust");
+
+        changeset.invert().apply(&mut rope);
+        assert_eq!(rope.to_string(), base.to_string());
+    }
+
+    #[test]
+    fn map_pos_bias_breaks_ties_at_an_insertion_point() {
+        let base = Rope::from_str("ac");
+        let mut scratch = base.clone();
+        let edit = scratch.build_edit(&change(0, 1, 0, 1, "B"), PositionEncoding::Utf8).unwrap();
+        let changeset = ChangeSet::from_edit(&scratch, &edit);
+
+        assert_eq!(changeset.map_pos(1, Bias::Before), 1);
+        assert_eq!(changeset.map_pos(1, Bias::After), 2);
+    }
+
+    #[test]
+    fn input_edits_merges_adjacent_delete_and_insert_into_one_edit() {
+        let base = Rope::from_str("hello world");
+        let mut scratch = base.clone();
+        let edit = scratch.build_edit(&change(0, 6, 0, 11, "rust"), PositionEncoding::Utf8).unwrap();
+        let changeset = ChangeSet::from_edit(&scratch, &edit);
+
+        let edits = changeset.input_edits();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].start_byte(), 6);
+        assert_eq!(edits[0].old_end_byte(), 11);
+        assert_eq!(edits[0].new_end_byte(), 10);
+    }
+}
@@ -2,8 +2,12 @@
 #![deny(unsafe_code)]
 #![allow(clippy::needless_lifetimes)]
 
+mod anchor;
+mod change_set;
 mod rope;
 mod text;
 
+pub use anchor::*;
+pub use change_set::*;
 pub use rope::*;
 pub use text::*;
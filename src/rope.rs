@@ -1,4 +1,7 @@
-use crate::text::{TextEdit, TextPosition};
+use crate::{
+    anchor::AnchorSet,
+    text::{Bias, LineEnding, PositionEncoding, TextEdit, TextPosition},
+};
 use bytes::Bytes;
 use ropey::{iter::Chunks, Rope};
 use std::{borrow::Cow, convert::TryFrom};
@@ -18,6 +21,143 @@ impl<'a> ChunkExt<'a> for Chunks<'a> {
     }
 }
 
+/// The number of bytes occupied by the line terminator (`\r\n`, `\r`, or `\n`) ending just before
+/// `byte_idx`, or `0` if there isn't one (e.g. the document's last line).
+fn line_ending_len_before(rope: &Rope, byte_idx: usize) -> usize {
+    if byte_idx >= 2 && rope.byte(byte_idx - 2) == b'\r' && rope.byte(byte_idx - 1) == b'\n' {
+        2
+    } else if byte_idx >= 1 && matches!(rope.byte(byte_idx - 1), b'\r' | b'\n') {
+        1
+    } else {
+        0
+    }
+}
+
+/// The byte offset marking the end of `line_idx`'s content, excluding its own line terminator (if
+/// it has one).
+fn line_content_end_byte(rope: &Rope, line_idx: usize) -> usize {
+    if line_idx + 1 < rope.len_lines() {
+        let next_line_start_byte = rope.line_to_byte(line_idx + 1);
+        next_line_start_byte - line_ending_len_before(rope, next_line_start_byte)
+    } else {
+        rope.len_bytes()
+    }
+}
+
+/// The length of `line_idx`, expressed in `encoding` units, treating the line as running up to
+/// (but not including) its own line terminator, if it has one.
+fn line_len_in_encoding(rope: &Rope, line_idx: usize, encoding: PositionEncoding) -> usize {
+    let line_start_byte = rope.line_to_byte(line_idx);
+    let line_end_byte = line_content_end_byte(rope, line_idx);
+
+    match encoding {
+        PositionEncoding::Utf8 => line_end_byte - line_start_byte,
+        PositionEncoding::Utf16 => {
+            let start_char = rope.byte_to_char(line_start_byte);
+            let end_char = rope.byte_to_char(line_end_byte);
+            rope.char_to_utf16_cu(end_char) - rope.char_to_utf16_cu(start_char)
+        },
+        PositionEncoding::Utf32 => {
+            let start_char = rope.byte_to_char(line_start_byte);
+            let end_char = rope.byte_to_char(line_end_byte);
+            end_char - start_char
+        },
+    }
+}
+
+/// The length of `line_idx` up to (but not including) its last character, expressed in `encoding`
+/// units. Unlike subtracting one encoding unit from [`line_len_in_encoding`], this always lands on
+/// a char boundary, even when the last character is wider than one unit in `encoding` (e.g. an
+/// astral character under [`PositionEncoding::Utf16`], which is a two-unit surrogate pair).
+fn line_len_before_last_char_in_encoding(rope: &Rope, line_idx: usize, encoding: PositionEncoding) -> usize {
+    let line_start_byte = rope.line_to_byte(line_idx);
+    let content_end_byte = line_content_end_byte(rope, line_idx);
+    if content_end_byte <= line_start_byte {
+        return 0;
+    }
+
+    let content_end_char = rope.byte_to_char(content_end_byte);
+    let last_char_start_char = content_end_char - 1;
+
+    match encoding {
+        PositionEncoding::Utf8 => rope.char_to_byte(last_char_start_char) - line_start_byte,
+        PositionEncoding::Utf16 => {
+            let start_char = rope.byte_to_char(line_start_byte);
+            rope.char_to_utf16_cu(last_char_start_char) - rope.char_to_utf16_cu(start_char)
+        },
+        PositionEncoding::Utf32 => {
+            let start_char = rope.byte_to_char(line_start_byte);
+            last_char_start_char - start_char
+        },
+    }
+}
+
+/// Treats `position` as "unclipped" (i.e. possibly referring to a line or column past the end of
+/// the document) and clamps it to a valid position, reporting whether clamping occurred.
+fn clamp_lsp_position(
+    rope: &Rope,
+    position: lsp::Position,
+    encoding: PositionEncoding,
+    bias: Bias,
+) -> (lsp::Position, bool) {
+    let mut clamped = false;
+
+    let max_line_idx = rope.len_lines().saturating_sub(1);
+    let line_idx = if position.line as usize <= max_line_idx {
+        position.line as usize
+    } else {
+        clamped = true;
+        max_line_idx
+    };
+
+    let max_character = line_len_in_encoding(rope, line_idx, encoding);
+    let character = if position.character as usize <= max_character {
+        position.character as usize
+    } else {
+        clamped = true;
+        match bias {
+            Bias::Before => line_len_before_last_char_in_encoding(rope, line_idx, encoding),
+            Bias::After => max_character,
+        }
+    };
+
+    (lsp::Position::new(line_idx as u32, character as u32), clamped)
+}
+
+/// If `byte_idx` falls between the `\r` and `\n` of a CRLF pair, snaps it back to just before the
+/// `\r` so a caller never ends up with a position that would split the line ending in two.
+fn snap_out_of_crlf(rope: &Rope, byte_idx: usize) -> usize {
+    if byte_idx > 0 && byte_idx < rope.len_bytes() && rope.byte(byte_idx - 1) == b'\r' && rope.byte(byte_idx) == b'\n'
+    {
+        byte_idx - 1
+    } else {
+        byte_idx
+    }
+}
+
+/// Rewrites every `\r\n`, lone `\r`, and lone `\n` in `text` to `ending`.
+fn normalize_line_endings(text: &str, ending: LineEnding) -> Cow<'_, str> {
+    if !text.contains(['\r', '\n']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut normalized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                normalized.push_str(ending.as_str());
+            },
+            '\n' => normalized.push_str(ending.as_str()),
+            _ => normalized.push(c),
+        }
+    }
+    Cow::Owned(normalized)
+}
+
 pub struct ChunkWalker {
     rope: Rope,
     cursor: usize,
@@ -42,18 +182,78 @@ impl ChunkWalker {
         }
     }
 
+    /// Repositions the cursor so its chunk contains `byte_idx`.
+    pub fn seek(&mut self, byte_idx: usize) {
+        while byte_idx < self.cursor && 0 < self.cursor {
+            self.retreat();
+        }
+        while byte_idx >= self.cursor + self.cursor_chunk.len() && self.cursor < self.rope.len_bytes() {
+            self.next_chunk();
+        }
+    }
+
+    /// The chunk the cursor is currently positioned over, and its start byte offset.
+    pub fn chunk(&self) -> (&str, usize) {
+        (self.cursor_chunk, self.cursor)
+    }
+
+    /// Moves the cursor to the next chunk, returning `false` if it was already at the end of the
+    /// rope.
+    pub fn advance(&mut self) -> bool {
+        if self.cursor + self.cursor_chunk.len() >= self.rope.len_bytes() {
+            return false;
+        }
+        self.next_chunk();
+        true
+    }
+
+    /// Moves the cursor to the previous chunk, returning `false` if it was already at the start
+    /// of the rope.
+    pub fn retreat(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        // `self.chunks` sits just past the current chunk, so the first `prev` yields the current
+        // chunk again; the second yields the previous one, and `next` steps back past it so the
+        // iterator again sits just past the cursor's chunk.
+        self.chunks.prev_str();
+        self.cursor_chunk = self.chunks.prev_str();
+        self.chunks.next_str();
+        self.cursor -= self.cursor_chunk.len();
+        true
+    }
+
+    /// The chunk after the cursor's current chunk, and its start byte offset, without moving the
+    /// cursor.
+    ///
+    /// Re-derives the chunk from `self.rope` rather than cloning `self.chunks`: that iterator
+    /// borrows through the self-referential `'static` transmute in [`RopeExt::chunk_walker`], and
+    /// cloning it out of `&self` would let the clone outlive the borrow it's secretly tied to.
+    pub fn peek_next(&self) -> Option<(&str, usize)> {
+        let offset = self.cursor + self.cursor_chunk.len();
+        if offset >= self.rope.len_bytes() {
+            return None;
+        }
+        let (chunk, chunk_byte_idx, ..) = self.rope.chunk_at_byte(offset);
+        Some((chunk, chunk_byte_idx))
+    }
+
+    /// The chunk before the cursor's current chunk, and its start byte offset, without moving the
+    /// cursor. See [`ChunkWalker::peek_next`] for why this goes through `self.rope` instead of
+    /// cloning `self.chunks`.
+    pub fn peek_prev(&self) -> Option<(&str, usize)> {
+        if self.cursor == 0 {
+            return None;
+        }
+        let (chunk, chunk_byte_idx, ..) = self.rope.chunk_at_byte(self.cursor - 1);
+        Some((chunk, chunk_byte_idx))
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     pub fn callback_adapter(mut self) -> impl FnMut(u32, tree_sitter::Point) -> Bytes {
         move |start_index, _position| {
             let start_index = start_index as usize;
-
-            while start_index < self.cursor && 0 < self.cursor {
-                self.prev_chunk();
-            }
-
-            while start_index >= self.cursor + self.cursor_chunk.len() && start_index < self.rope.len_bytes() {
-                self.next_chunk();
-            }
+            self.seek(start_index);
 
             let bytes = self.cursor_chunk.as_bytes();
             let bytes = &bytes[start_index - self.cursor ..];
@@ -65,14 +265,7 @@ impl ChunkWalker {
     pub fn callback_adapter(mut self) -> impl FnMut(u32, Option<tree_sitter::Point>, Option<u32>) -> Bytes {
         move |start_index, _position, end_index| {
             let start_index = start_index as usize;
-
-            while start_index < self.cursor && 0 < self.cursor {
-                self.prev_chunk();
-            }
-
-            while start_index >= self.cursor + self.cursor_chunk.len() && start_index < self.rope.len_bytes() {
-                self.next_chunk();
-            }
+            self.seek(start_index);
 
             let bytes = self.cursor_chunk.as_bytes();
             let end_index = end_index.map(|i| i as usize).unwrap_or_else(|| bytes.len());
@@ -84,14 +277,47 @@ impl ChunkWalker {
 
 pub trait RopeExt {
     fn apply_edit(&mut self, edit: &TextEdit);
-    fn build_edit<'a>(&self, change: &'a lsp::TextDocumentContentChangeEvent) -> anyhow::Result<TextEdit<'a>>;
-    fn byte_to_lsp_position(&self, offset: usize) -> lsp::Position;
+    /// Applies `edit` and updates every anchor in `anchors` in place, so callers don't have to
+    /// re-resolve their anchors from scratch after every mutation.
+    fn apply_edit_with_anchors(&mut self, edit: &TextEdit, anchors: &mut AnchorSet) {
+        anchors.apply_edit(edit);
+        self.apply_edit(edit);
+    }
+    /// Applies `edit`, first normalizing its inserted text's line endings to match
+    /// [`RopeExt::line_ending`], and returns an updated `TextEdit` reflecting the normalized text
+    /// actually inserted (normalization can change its byte length, so `edit` itself may no
+    /// longer describe the change once applied).
+    fn apply_edit_normalized(&mut self, edit: &TextEdit) -> anyhow::Result<TextEdit<'static>>;
+    /// Detects the dominant line terminator used in the document.
+    fn line_ending(&self) -> LineEnding;
+    fn build_edit<'a>(
+        &self,
+        change: &'a lsp::TextDocumentContentChangeEvent,
+        encoding: PositionEncoding,
+    ) -> anyhow::Result<TextEdit<'a>>;
+    fn byte_to_lsp_position(&self, offset: usize, encoding: PositionEncoding) -> lsp::Position;
     fn byte_to_tree_sitter_point(&self, offset: usize) -> anyhow::Result<tree_sitter::Point>;
     fn chunk_walker(self, byte_idx: usize) -> ChunkWalker;
-    fn lsp_position_to_core(&self, position: lsp::Position) -> anyhow::Result<TextPosition>;
-    fn lsp_position_to_utf16_cu(&self, position: lsp::Position) -> anyhow::Result<u32>;
-    fn lsp_range_to_tree_sitter_range(&self, range: lsp::Range) -> anyhow::Result<tree_sitter::Range>;
-    fn tree_sitter_range_to_lsp_range(&self, range: tree_sitter::Range) -> lsp::Range;
+    fn lsp_position_to_core(&self, position: lsp::Position, encoding: PositionEncoding) -> anyhow::Result<TextPosition>;
+    fn lsp_position_to_core_clamped(
+        &self,
+        position: lsp::Position,
+        encoding: PositionEncoding,
+        bias: Bias,
+    ) -> anyhow::Result<(TextPosition, bool)>;
+    fn lsp_position_to_utf16_cu(&self, position: lsp::Position, encoding: PositionEncoding) -> anyhow::Result<u32>;
+    fn lsp_position_to_utf16_cu_clamped(
+        &self,
+        position: lsp::Position,
+        encoding: PositionEncoding,
+        bias: Bias,
+    ) -> anyhow::Result<(u32, bool)>;
+    fn lsp_range_to_tree_sitter_range(
+        &self,
+        range: lsp::Range,
+        encoding: PositionEncoding,
+    ) -> anyhow::Result<tree_sitter::Range>;
+    fn tree_sitter_range_to_lsp_range(&self, range: tree_sitter::Range, encoding: PositionEncoding) -> lsp::Range;
     fn utf8_text_for_tree_sitter_node<'rope, 'tree>(&'rope self, node: &tree_sitter::Node<'tree>) -> Cow<'rope, str>;
 }
 
@@ -103,7 +329,11 @@ impl RopeExt for Rope {
         }
     }
 
-    fn build_edit<'a>(&self, change: &'a lsp::TextDocumentContentChangeEvent) -> anyhow::Result<TextEdit<'a>> {
+    fn build_edit<'a>(
+        &self,
+        change: &'a lsp::TextDocumentContentChangeEvent,
+        encoding: PositionEncoding,
+    ) -> anyhow::Result<TextEdit<'a>> {
         let text = change.text.as_str();
         let text_bytes = text.as_bytes();
         let text_end_byte_idx = text_bytes.len();
@@ -111,13 +341,13 @@ impl RopeExt for Rope {
         let range = if let Some(range) = change.range {
             range
         } else {
-            let start = self.byte_to_lsp_position(0);
-            let end = self.byte_to_lsp_position(text_end_byte_idx);
+            let start = self.byte_to_lsp_position(0, encoding);
+            let end = self.byte_to_lsp_position(text_end_byte_idx, encoding);
             lsp::Range { start, end }
         };
 
-        let start = self.lsp_position_to_core(range.start)?;
-        let old_end = self.lsp_position_to_core(range.end)?;
+        let start = self.lsp_position_to_core(range.start, encoding)?;
+        let old_end = self.lsp_position_to_core(range.end, encoding)?;
 
         let new_end_byte = start.byte as usize + text_end_byte_idx;
         let new_end_position = self.byte_to_tree_sitter_point(new_end_byte)?;
@@ -142,29 +372,123 @@ impl RopeExt for Rope {
             input_edit,
             start_char_idx: start.char as usize,
             end_char_idx: old_end.char as usize,
-            text,
+            text: Cow::Borrowed(text),
         })
     }
 
-    fn byte_to_lsp_position(&self, byte_idx: usize) -> lsp::Position {
-        let line_idx = self.byte_to_line(byte_idx);
+    /// Applies `edit`, first normalizing its inserted text's line endings to match
+    /// [`RopeExt::line_ending`]. Normalization can change the inserted byte length (e.g. a CRLF
+    /// document receiving a lone `\n`), so `edit.input_edit` can't be reused as-is; this returns a
+    /// fresh `TextEdit` whose `input_edit` and `text` describe what was actually inserted.
+    fn apply_edit_normalized(&mut self, edit: &TextEdit) -> anyhow::Result<TextEdit<'static>> {
+        let ending = self.line_ending();
+        let normalized = normalize_line_endings(&edit.text, ending).into_owned();
 
-        let line_utf16_cu_idx = {
-            let char_idx = self.line_to_char(line_idx);
-            self.char_to_utf16_cu(char_idx)
-        };
+        let start_byte = edit.input_edit.start_byte();
+        let old_end_byte = edit.input_edit.old_end_byte();
+        let new_end_byte = start_byte + u32::try_from(normalized.len())?;
+
+        self.remove(edit.start_char_idx .. edit.end_char_idx);
+        if !normalized.is_empty() {
+            self.insert(edit.start_char_idx, &normalized);
+        }
+
+        let new_end_position = self.byte_to_tree_sitter_point(new_end_byte as usize)?;
+
+        let input_edit = tree_sitter::InputEdit::new(
+            start_byte,
+            old_end_byte,
+            new_end_byte,
+            &edit.input_edit.start_position(),
+            &edit.input_edit.old_end_position(),
+            &new_end_position,
+        );
+
+        Ok(TextEdit {
+            input_edit,
+            start_char_idx: edit.start_char_idx,
+            end_char_idx: edit.end_char_idx,
+            text: Cow::Owned(normalized),
+        })
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        let mut crlf = 0usize;
+        let mut lf = 0usize;
+        let mut cr = 0usize;
+        let mut pending_cr = false;
+
+        for chunk in self.chunks() {
+            let bytes = chunk.as_bytes();
+            let mut i = 0;
+
+            if pending_cr {
+                if bytes.first() == Some(&b'\n') {
+                    crlf += 1;
+                    i = 1;
+                } else {
+                    cr += 1;
+                }
+                pending_cr = false;
+            }
 
-        let character_utf16_cu_idx = {
-            let char_idx = self.byte_to_char(byte_idx);
-            self.char_to_utf16_cu(char_idx)
+            while i < bytes.len() {
+                match bytes[i] {
+                    b'\r' if i + 1 < bytes.len() && bytes[i + 1] == b'\n' => {
+                        crlf += 1;
+                        i += 2;
+                        continue;
+                    },
+                    b'\r' if i + 1 < bytes.len() => cr += 1,
+                    b'\r' => pending_cr = true,
+                    b'\n' => lf += 1,
+                    _ => {},
+                }
+                i += 1;
+            }
+        }
+        if pending_cr {
+            cr += 1;
+        }
+
+        if crlf == 0 && lf == 0 && cr == 0 {
+            LineEnding::default()
+        } else if crlf >= lf && crlf >= cr {
+            LineEnding::Crlf
+        } else if cr > lf {
+            LineEnding::Cr
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    fn byte_to_lsp_position(&self, byte_idx: usize, encoding: PositionEncoding) -> lsp::Position {
+        let byte_idx = snap_out_of_crlf(self, byte_idx);
+        let line_idx = self.byte_to_line(byte_idx);
+
+        let character = match encoding {
+            PositionEncoding::Utf8 => {
+                let line_byte_idx = self.line_to_byte(line_idx);
+                byte_idx - line_byte_idx
+            },
+            PositionEncoding::Utf16 => {
+                let line_utf16_cu_idx = self.char_to_utf16_cu(self.line_to_char(line_idx));
+                let character_utf16_cu_idx = self.char_to_utf16_cu(self.byte_to_char(byte_idx));
+                character_utf16_cu_idx - line_utf16_cu_idx
+            },
+            PositionEncoding::Utf32 => {
+                let line_char_idx = self.line_to_char(line_idx);
+                self.byte_to_char(byte_idx) - line_char_idx
+            },
         };
 
         let line = line_idx;
-        let character = character_utf16_cu_idx - line_utf16_cu_idx;
-
         lsp::Position::new(line as u32, character as u32)
     }
 
+    /// Does *not* snap out of a CRLF pair like [`RopeExt::byte_to_lsp_position`] does: callers
+    /// such as [`RopeExt::build_edit`] feed this the same byte offset they hand tree-sitter's
+    /// `InputEdit`, and that byte/point pair must agree exactly.
     fn byte_to_tree_sitter_point(&self, byte_idx: usize) -> anyhow::Result<tree_sitter::Point> {
         let line_idx = self.byte_to_line(byte_idx);
         let line_byte_idx = self.line_to_byte(line_idx);
@@ -187,54 +511,98 @@ impl RopeExt for Rope {
         }
     }
 
-    fn lsp_position_to_core(&self, position: lsp::Position) -> anyhow::Result<TextPosition> {
+    fn lsp_position_to_core(&self, position: lsp::Position, encoding: PositionEncoding) -> anyhow::Result<TextPosition> {
         let row_idx = position.line as usize;
-
-        let col_code_idx = position.character as usize;
-
         let row_char_idx = self.line_to_char(row_idx);
-        let col_char_idx = self.utf16_cu_to_char(col_code_idx);
-
         let row_byte_idx = self.line_to_byte(row_idx);
-        let col_byte_idx = self.char_to_byte(col_char_idx);
 
-        let row_code_idx = self.char_to_utf16_cu(row_char_idx);
+        let char_idx = match encoding {
+            PositionEncoding::Utf8 => {
+                let col_byte_idx = position.character as usize;
+                self.byte_to_char(row_byte_idx + col_byte_idx)
+            },
+            PositionEncoding::Utf16 => {
+                let row_code_idx = self.char_to_utf16_cu(row_char_idx);
+                let col_code_idx = position.character as usize;
+                self.utf16_cu_to_char(row_code_idx + col_code_idx)
+            },
+            PositionEncoding::Utf32 => row_char_idx + position.character as usize,
+        };
+
+        let byte_idx = self.char_to_byte(char_idx);
+        let code_idx = self.char_to_utf16_cu(char_idx);
 
         let point = {
             let row = position.line;
-            let col = u32::try_from(col_byte_idx)?;
+            let col = u32::try_from(byte_idx - row_byte_idx)?;
             tree_sitter::Point::new(row, col)
         };
 
         Ok(TextPosition {
-            char: u32::try_from(row_char_idx + col_char_idx)?,
-            byte: u32::try_from(row_byte_idx + col_byte_idx)?,
-            code: u32::try_from(row_code_idx + col_code_idx)?,
+            char: u32::try_from(char_idx)?,
+            byte: u32::try_from(byte_idx)?,
+            code: u32::try_from(code_idx)?,
             point,
         })
     }
 
-    fn lsp_position_to_utf16_cu(&self, position: lsp::Position) -> anyhow::Result<u32> {
-        let line_idx = position.line as usize;
-        let line_utf16_cu_idx = {
-            let char_idx = self.line_to_char(line_idx);
-            self.char_to_utf16_cu(char_idx)
+    fn lsp_position_to_core_clamped(
+        &self,
+        position: lsp::Position,
+        encoding: PositionEncoding,
+        bias: Bias,
+    ) -> anyhow::Result<(TextPosition, bool)> {
+        let (position, clamped) = clamp_lsp_position(self, position, encoding, bias);
+        let text_position = self.lsp_position_to_core(position, encoding)?;
+        Ok((text_position, clamped))
+    }
+
+    fn lsp_position_to_utf16_cu(&self, position: lsp::Position, encoding: PositionEncoding) -> anyhow::Result<u32> {
+        let row_idx = position.line as usize;
+        let row_char_idx = self.line_to_char(row_idx);
+
+        let char_idx = match encoding {
+            PositionEncoding::Utf8 => {
+                let row_byte_idx = self.line_to_byte(row_idx);
+                self.byte_to_char(row_byte_idx + position.character as usize)
+            },
+            PositionEncoding::Utf16 => {
+                let row_code_idx = self.char_to_utf16_cu(row_char_idx);
+                let result = u32::try_from(row_code_idx + position.character as usize)?;
+                return Ok(result);
+            },
+            PositionEncoding::Utf32 => row_char_idx + position.character as usize,
         };
-        let char_utf16_cu_idx = position.character as usize;
-        let result = u32::try_from(line_utf16_cu_idx + char_utf16_cu_idx)?;
+
+        let result = u32::try_from(self.char_to_utf16_cu(char_idx))?;
         Ok(result)
     }
 
-    fn lsp_range_to_tree_sitter_range(&self, range: lsp::Range) -> anyhow::Result<tree_sitter::Range> {
-        let start = self.lsp_position_to_core(range.start)?;
-        let end = self.lsp_position_to_core(range.end)?;
+    fn lsp_position_to_utf16_cu_clamped(
+        &self,
+        position: lsp::Position,
+        encoding: PositionEncoding,
+        bias: Bias,
+    ) -> anyhow::Result<(u32, bool)> {
+        let (position, clamped) = clamp_lsp_position(self, position, encoding, bias);
+        let code = self.lsp_position_to_utf16_cu(position, encoding)?;
+        Ok((code, clamped))
+    }
+
+    fn lsp_range_to_tree_sitter_range(
+        &self,
+        range: lsp::Range,
+        encoding: PositionEncoding,
+    ) -> anyhow::Result<tree_sitter::Range> {
+        let start = self.lsp_position_to_core(range.start, encoding)?;
+        let end = self.lsp_position_to_core(range.end, encoding)?;
         let range = tree_sitter::Range::new(start.byte, end.byte, &start.point, &end.point);
         Ok(range)
     }
 
-    fn tree_sitter_range_to_lsp_range(&self, range: tree_sitter::Range) -> lsp::Range {
-        let start = self.byte_to_lsp_position(range.start_byte() as usize);
-        let end = self.byte_to_lsp_position(range.end_byte() as usize);
+    fn tree_sitter_range_to_lsp_range(&self, range: tree_sitter::Range, encoding: PositionEncoding) -> lsp::Range {
+        let start = self.byte_to_lsp_position(range.start_byte() as usize, encoding);
+        let end = self.byte_to_lsp_position(range.end_byte() as usize, encoding);
         lsp::Range::new(start, end)
     }
 
@@ -244,4 +612,219 @@ impl RopeExt for Rope {
         let slice = self.slice(start .. end);
         slice.into()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A rope large enough that ropey splits it into several chunks, so walker tests actually
+    /// exercise chunk boundaries instead of a single-chunk rope.
+    fn multi_chunk_rope() -> Rope {
+        Rope::from_str(&"0123456789".repeat(1000))
+    }
+
+    #[test]
+    fn peek_next_does_not_move_the_cursor_and_matches_advance() {
+        let rope = multi_chunk_rope();
+        let mut walker = rope.clone().chunk_walker(0);
+
+        let (before_chunk, before_cursor) = walker.chunk();
+        let peeked = walker
+            .peek_next()
+            .map(|(chunk, byte_idx)| (chunk.to_owned(), byte_idx))
+            .expect("rope has more than one chunk");
+        assert_eq!(walker.chunk(), (before_chunk, before_cursor));
+
+        assert!(walker.advance());
+        assert_eq!(walker.chunk(), (peeked.0.as_str(), peeked.1));
+    }
+
+    #[test]
+    fn peek_prev_does_not_move_the_cursor_and_matches_retreat() {
+        let rope = multi_chunk_rope();
+        let mut walker = rope.clone().chunk_walker(0);
+        assert!(walker.advance());
+        assert!(walker.advance());
+
+        let (before_chunk, before_cursor) = walker.chunk();
+        let peeked = walker
+            .peek_prev()
+            .map(|(chunk, byte_idx)| (chunk.to_owned(), byte_idx))
+            .expect("cursor is past the first chunk");
+        assert_eq!(walker.chunk(), (before_chunk, before_cursor));
+
+        assert!(walker.retreat());
+        assert_eq!(walker.chunk(), (peeked.0.as_str(), peeked.1));
+    }
+
+    #[test]
+    fn peek_next_is_none_at_the_last_chunk() {
+        let rope = Rope::from_str("a");
+        let walker = rope.chunk_walker(0);
+        assert_eq!(walker.peek_next(), None);
+    }
+
+    #[test]
+    fn peek_prev_is_none_at_the_first_chunk() {
+        let rope = multi_chunk_rope();
+        let walker = rope.chunk_walker(0);
+        assert_eq!(walker.peek_prev(), None);
+    }
+
+    #[test]
+    fn clamp_before_bias_lands_on_the_start_of_an_astral_last_char_under_utf16() {
+        // '𝄞' is a 2-code-unit UTF-16 surrogate pair; clamping "before" an out-of-range character
+        // must land before the whole pair, not between its two halves.
+        let rope = Rope::from_str("a𝄞");
+        let (position, clamped) =
+            clamp_lsp_position(&rope, lsp::Position::new(0, 100), PositionEncoding::Utf16, Bias::Before);
+        assert!(clamped);
+        assert_eq!(position, lsp::Position::new(0, 1));
+
+        let resolved = rope.lsp_position_to_core(position, PositionEncoding::Utf16).unwrap();
+        assert_eq!(resolved.byte, 1);
+    }
+
+    #[test]
+    fn clamp_excludes_crlf_terminator_from_max_character() {
+        let rope = Rope::from_str("ab\r\ncd");
+        let (position, clamped) = clamp_lsp_position(
+            &rope,
+            lsp::Position::new(0, 100),
+            PositionEncoding::Utf8,
+            Bias::After,
+        );
+        assert!(clamped);
+        assert_eq!(position, lsp::Position::new(0, 2));
+    }
+
+    #[test]
+    fn clamp_excludes_lf_terminator_from_max_character() {
+        let rope = Rope::from_str("ab\ncd");
+        let (position, clamped) = clamp_lsp_position(
+            &rope,
+            lsp::Position::new(0, 100),
+            PositionEncoding::Utf8,
+            Bias::After,
+        );
+        assert!(clamped);
+        assert_eq!(position, lsp::Position::new(0, 2));
+    }
+
+    #[test]
+    fn clamp_last_line_has_no_terminator_to_exclude() {
+        let rope = Rope::from_str("ab\ncd");
+        let (position, clamped) = clamp_lsp_position(
+            &rope,
+            lsp::Position::new(1, 100),
+            PositionEncoding::Utf8,
+            Bias::After,
+        );
+        assert!(clamped);
+        assert_eq!(position, lsp::Position::new(1, 2));
+    }
+
+    #[test]
+    fn build_edit_new_end_byte_and_point_agree_across_a_crlf_pair() {
+        // "ab\r\ncd": inserting "X" right after "ab" puts the edit's new end at byte 3, which in
+        // this rope falls between the "\r" and "\n"; byte and point must still describe the same
+        // offset, with neither side snapping out of the pair.
+        let rope = Rope::from_str("ab\r\ncd");
+        let change = lsp::TextDocumentContentChangeEvent {
+            range: Some(lsp::Range::new(lsp::Position::new(0, 2), lsp::Position::new(0, 2))),
+            range_length: None,
+            text: "X".to_string(),
+        };
+        let edit = rope.build_edit(&change, PositionEncoding::Utf8).unwrap();
+        let new_end_byte = edit.input_edit.new_end_byte() as usize;
+        let new_end_point = edit.input_edit.new_end_position();
+        let point_byte =
+            rope.line_to_byte(new_end_point.row as usize) + new_end_point.column as usize;
+        assert_eq!(new_end_byte, point_byte);
+    }
+
+    #[test]
+    fn apply_edit_normalized_recomputes_the_new_end_for_a_length_changing_normalization() {
+        // The document is CRLF, but the incoming change inserts a lone "\n"; normalizing it to
+        // "\r\n" grows the insert by one byte, so the returned edit's new_end_byte must reflect
+        // that growth rather than the un-normalized edit's.
+        let mut rope = Rope::from_str("ab\r\ncd");
+        let change = lsp::TextDocumentContentChangeEvent {
+            range: Some(lsp::Range::new(lsp::Position::new(1, 0), lsp::Position::new(1, 0))),
+            range_length: None,
+            text: "\n".to_string(),
+        };
+        let edit = rope.build_edit(&change, PositionEncoding::Utf8).unwrap();
+        assert_eq!(edit.input_edit.new_end_byte() - edit.input_edit.start_byte(), 1);
+
+        let normalized_edit = rope.apply_edit_normalized(&edit).unwrap();
+        assert_eq!(normalized_edit.text, "\r\n");
+        assert_eq!(
+            normalized_edit.input_edit.new_end_byte() - normalized_edit.input_edit.start_byte(),
+            2
+        );
+        assert_eq!(rope.to_string(), "ab\r\n\r\ncd");
+    }
+
+    #[test]
+    fn utf8_round_trips_a_multibyte_char() {
+        // 'é' is 2 UTF-8 bytes; position after "h" + "é".
+        let rope = Rope::from_str("héllo");
+        let byte_idx = 3;
+        let position = rope.byte_to_lsp_position(byte_idx, PositionEncoding::Utf8);
+        assert_eq!(position, lsp::Position::new(0, 3));
+        let resolved = rope.lsp_position_to_core(position, PositionEncoding::Utf8).unwrap();
+        assert_eq!(resolved.byte as usize, byte_idx);
+    }
+
+    #[test]
+    fn utf16_round_trips_a_multibyte_char() {
+        // 'é' is 1 UTF-16 code unit despite being 2 UTF-8 bytes.
+        let rope = Rope::from_str("héllo");
+        let byte_idx = 3;
+        let position = rope.byte_to_lsp_position(byte_idx, PositionEncoding::Utf16);
+        assert_eq!(position, lsp::Position::new(0, 2));
+        let resolved = rope.lsp_position_to_core(position, PositionEncoding::Utf16).unwrap();
+        assert_eq!(resolved.byte as usize, byte_idx);
+    }
+
+    #[test]
+    fn utf16_round_trips_an_astral_char_as_a_surrogate_pair() {
+        // '𝄞' (U+1D11E) is 4 UTF-8 bytes but a 2-code-unit UTF-16 surrogate pair.
+        let rope = Rope::from_str("a𝄞b");
+        let byte_idx = 1 + 4;
+        let position = rope.byte_to_lsp_position(byte_idx, PositionEncoding::Utf16);
+        assert_eq!(position, lsp::Position::new(0, 1 + 2));
+        let resolved = rope.lsp_position_to_core(position, PositionEncoding::Utf16).unwrap();
+        assert_eq!(resolved.byte as usize, byte_idx);
+    }
+
+    #[test]
+    fn utf32_round_trips_an_astral_char_as_one_unit() {
+        // '𝄞' is a single Unicode scalar value regardless of its UTF-8/UTF-16 width.
+        let rope = Rope::from_str("a𝄞b");
+        let byte_idx = 1 + 4;
+        let position = rope.byte_to_lsp_position(byte_idx, PositionEncoding::Utf32);
+        assert_eq!(position, lsp::Position::new(0, 2));
+        let resolved = rope.lsp_position_to_core(position, PositionEncoding::Utf32).unwrap();
+        assert_eq!(resolved.byte as usize, byte_idx);
+    }
+
+    #[test]
+    fn lsp_position_to_utf16_cu_counts_a_surrogate_pair_as_two_units() {
+        let rope = Rope::from_str("a𝄞b");
+        let position = lsp::Position::new(0, 1 + 2);
+        let code = rope.lsp_position_to_utf16_cu(position, PositionEncoding::Utf16).unwrap();
+        assert_eq!(code, 3);
+    }
+
+    #[test]
+    fn lsp_position_to_utf16_cu_from_a_utf8_encoded_position() {
+        let rope = Rope::from_str("a𝄞b");
+        let byte_idx = 1 + 4;
+        let position = lsp::Position::new(0, byte_idx as u32);
+        let code = rope.lsp_position_to_utf16_cu(position, PositionEncoding::Utf8).unwrap();
+        assert_eq!(code, 3);
+    }
 }
\ No newline at end of file
@@ -1,3 +1,55 @@
+/// The unit used to express the `character` component of an [`lsp::Position`](lsp::Position),
+/// as negotiated via the LSP 3.17 `positionEncoding` client/server capability.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionEncoding {
+    /// `character` is a byte offset within the line.
+    Utf8,
+    /// `character` is a UTF-16 code unit offset within the line.
+    Utf16,
+    /// `character` is a `char` (Unicode scalar value) offset within the line.
+    Utf32,
+}
+
+impl Default for PositionEncoding {
+    /// LSP defaults to UTF-16 when no `positionEncoding` is negotiated.
+    fn default() -> Self {
+        PositionEncoding::Utf16
+    }
+}
+
+/// The dominant line terminator detected in a document, so conversions can avoid splitting a
+/// `\r\n` pair and inserted text can be normalized to match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        LineEnding::Lf
+    }
+}
+
+/// Which side of a clamp a position resolves to when it has to move, e.g. when rounding a
+/// position down to the last valid unit on a line vs. up to the line's (one-past-the-end) length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bias {
+    Before,
+    After,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct TextPosition {
     pub char: u32,
@@ -11,7 +63,7 @@ pub struct TextEdit<'a> {
     pub input_edit: tree_sitter::InputEdit,
     pub start_char_idx: usize,
     pub end_char_idx: usize,
-    pub text: &'a str,
+    pub text: std::borrow::Cow<'a, str>,
 }
 
 impl<'a> TextEdit<'a> {